@@ -2,54 +2,542 @@
 // 考虑到您是Rust新手，我添加了详细的注释来解释每个部分的功能。
 
 // 从外部库（crates）和Rust标准库中导入必要的模块。
+use bimap::BiMap;
 use chardet::detect;
+use clap::{Parser, Subcommand, ValueEnum};
 use crossterm::{event, terminal};
 use encoding_rs::{Encoding, UTF_8};
-use serde::Deserialize;
+use rayon::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Mutex;
+use tar::{Builder, Header};
 use walkdir::WalkDir;
 
 // 这个结构体定义了我们的配置文件的结构。
 // `#[derive(Deserialize)]` 这个属性来自 `serde` 库。
 // 它会自动生成所需的代码，用来将TOML这样的格式解析（反序列化）到这个 `Config` 结构体中。
-#[derive(Deserialize, Debug)]
+// 这里的每个字段都是 `Option`，因为现在既可以从 `config.toml` 读取，
+// 也可以从命令行参数获取；缺失的字段稍后会和命令行参数合并。
+#[derive(Deserialize, Debug, Default)]
 struct Config {
+    path: Option<String>,
+    file_extension: Option<String>,
+    output_encoding: Option<String>,
+    input_encodings: Option<Vec<String>>,
+}
+
+// 这个结构体由 `clap` 负责解析命令行参数。
+// `#[derive(Parser)]` 会根据下面的字段自动生成一个参数解析器，例如：
+// `converter --path ./src --ext txt --to utf-8 --from gbk,big5`。
+#[derive(Parser, Debug)]
+#[command(name = "converter", about = "一个简单的文件编码转换器", version)]
+struct Cli {
+    // 配置文件的位置，默认仍然是 `config.toml`。
+    #[arg(long, default_value = "config.toml")]
+    config: String,
+
+    // 下面这些参数与 `Config` 一一对应，命令行提供时会覆盖配置文件里的值。
+    #[arg(long)]
+    path: Option<String>,
+
+    #[arg(long = "ext")]
+    file_extension: Option<String>,
+
+    #[arg(long = "to")]
+    output_encoding: Option<String>,
+
+    // `value_delimiter = ','` 让用户可以写成 `--from gbk,big5`。
+    #[arg(long = "from", value_delimiter = ',')]
+    input_encodings: Option<Vec<String>>,
+
+    // 并行处理目录时最多同时处理多少个文件。不指定时使用 CPU 核心数。
+    #[arg(long)]
+    jobs: Option<usize>,
+
+    // 只转换解码后内容匹配该正则的文件，例如 `--match "TODO|版权"`。
+    // 匹配发生在解码成 `String` 之后，因此与源编码无关。
+    #[arg(long = "match")]
+    pattern: Option<String>,
+
+    // 只打印匹配的文件路径和行号（类似 grep），不改写任何文件。
+    // 只在配合 `--match` 时才有意义，因此要求同时提供 pattern。
+    #[arg(long = "list-only", requires = "pattern")]
+    list_only: bool,
+
+    // 在第一次写入前，把所有将被改写的文件打包进一个 gzip 压缩的 tar 包，
+    // 例如 `--backup archive.tar.gz`，万一检测出错还能从中恢复。
+    #[arg(long)]
+    backup: Option<String>,
+
+    // 字符码表（CSV，列形如 `letter,code`），配合 `--encode`/`--decode` 使用。
+    #[arg(long)]
+    table: Option<String>,
+
+    // `--encode`：把每个源字符替换成码表中的编码，编码之间用 `--sep` 连接。
+    #[arg(long, requires = "table")]
+    encode: bool,
+
+    // `--decode`：用码表反向还原，对没有反向映射的 token 会报错而不是丢弃。
+    #[arg(long, requires = "table", conflicts_with = "encode")]
+    decode: bool,
+
+    // `--encode` 时用来连接各个编码、`--decode` 时用来切分 token 的分隔符。
+    #[arg(long, default_value = " ")]
+    sep: String,
+
+    // 把重新编码后的字节再做一层文本编码（base64 或 hex），方便嵌进 JS/JSON/源码里。
+    #[arg(long, value_enum)]
+    wrap: Option<WrapFormat>,
+
+    // `--unwrap`：反向操作，先把文件内容从 `--wrap` 指定的文本编码还原成原始字节，
+    // 再进入常规的编码转换流程。
+    #[arg(long, requires = "wrap")]
+    unwrap: bool,
+
+    // 忽略 `.conv-manifest.toml`，强制重新转换所有文件。
+    #[arg(long)]
+    force: bool,
+
+    // 子命令让这个工具将来可以扩展更多模式（仅检测、转换等），
+    // 同时不破坏现在的默认流程。
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+// 目前支持的子命令。不指定子命令时等同于 `convert`。
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    // 按配置转换文件编码（默认行为）。
+    Convert,
+    // 只检测并打印编码，不改写任何文件。
+    Detect,
+}
+
+// 合并后的、所有字段都已确定的配置。
+// 这是在 `Config`（来自TOML）和 `Cli`（来自命令行）合并之后得到的结果。
+#[derive(Debug)]
+struct ResolvedConfig {
     path: String,
     file_extension: String,
     output_encoding: String,
     input_encodings: Vec<String>,
 }
 
+// 单个文件处理完成后的结果。目录模式下会把它们收集起来，
+// 等所有线程都结束再统一打印汇总，避免多个线程交错输出。
+enum FileOutcome {
+    // 文件已成功（重新）写入。
+    Converted,
+    // 文件被有意跳过（例如仅检测模式），未做改动。
+    Skipped,
+}
+
+// 每个文件的一份报告：路径、结果，以及处理过程中产生的日志。
+// `log` 在线程内部累积，最后由主线程按原始顺序打印。
+struct FileReport {
+    path: PathBuf,
+    result: Result<FileOutcome, String>,
+    log: Vec<String>,
+}
+
+// 一个只追加的备份归档：把原始文件在被覆盖前写进一个 gzip 压缩的 tar 包。
+// 目录模式下多个线程会并发地往同一个归档里写，所以用 `Mutex` 串行化追加。
+struct Backup {
+    archive: Mutex<Builder<GzEncoder<fs::File>>>,
+}
+
+impl Backup {
+    // 在 `dest` 处新建一个 `.tar.gz` 归档。
+    fn create(dest: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = fs::File::create(dest)?;
+        let encoder = GzEncoder::new(file, Compression::default());
+        Ok(Backup {
+            archive: Mutex::new(Builder::new(encoder)),
+        })
+    }
+
+    // 以 `rel_path`（相对根目录的路径）把原始字节追加进归档。
+    fn add(&self, rel_path: &Path, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut archive = self.archive.lock().unwrap();
+        archive.append_data(&mut header, rel_path, data)?;
+        Ok(())
+    }
+
+    // 写出归档尾部并刷新底层的 gzip 流。
+    fn finish(self) -> Result<(), Box<dyn std::error::Error>> {
+        let encoder = self.archive.into_inner().unwrap().into_inner()?;
+        encoder.finish()?;
+        Ok(())
+    }
+}
+
+// 落盘的清单格式：相对路径 -> 内容哈希（SHA-256 的十六进制）。
+#[derive(Serialize, Deserialize, Default)]
+struct ManifestData {
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+}
+
+// 清单子系统：记录每个被转换文件的内容哈希，让重复运行变得幂等。
+// 下一次运行时，先对当前文件求哈希，命中记录就跳过，不再检测和改写。
+struct Manifest {
+    // 上一轮运行记下的哈希（只读），用于判断文件是否未改动。
+    previous: BTreeMap<String, String>,
+    // 本轮运行新记下的哈希。目录模式下多个线程会并发写入。
+    updated: Mutex<BTreeMap<String, String>>,
+}
+
+impl Manifest {
+    // 清单文件名，放在配置的根目录下。
+    const FILE_NAME: &'static str = ".conv-manifest.toml";
+
+    // 从根目录加载清单。`force` 为真时忽略已有记录（相当于全部重新转换）。
+    fn load(root: &Path, force: bool) -> Self {
+        let previous = if force {
+            BTreeMap::new()
+        } else {
+            fs::read_to_string(root.join(Self::FILE_NAME))
+                .ok()
+                .and_then(|content| toml::from_str::<ManifestData>(&content).ok())
+                .map(|data| data.files)
+                .unwrap_or_default()
+        };
+        Manifest {
+            previous,
+            updated: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // 当前文件的哈希是否与上一轮记录一致（即未改动、可跳过）。
+    fn is_unchanged(&self, key: &str, hash: &str) -> bool {
+        self.previous.get(key).map(|h| h == hash).unwrap_or(false)
+    }
+
+    // 记录某个文件本轮转换后的哈希。
+    fn record(&self, key: String, hash: String) {
+        self.updated.lock().unwrap().insert(key, hash);
+    }
+
+    // 把本轮的记录写回清单文件。跳过的文件沿用上一轮的哈希，避免审计信息丢失。
+    fn save(&self, root: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut files = self.previous.clone();
+        files.extend(self.updated.lock().unwrap().clone());
+        let data = ManifestData { files };
+        let content = toml::to_string(&data)?;
+        fs::write(root.join(Self::FILE_NAME), content)?;
+        Ok(())
+    }
+}
+
+// 计算字节内容的 SHA-256 十六进制哈希。
+fn content_hash(data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+    hex_encode(&digest)
+}
+
+// 码表转换的方向。
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Transform {
+    // 把源字符替换成编码。
+    Encode,
+    // 把编码还原成源字符。
+    Decode,
+}
+
+// 一个由 CSV 码表驱动的可逆编解码器。因为 `BiMap` 同时维护正反两个方向的映射，
+// 同一张表既能用来 `--encode` 也能用来 `--decode`。
+struct Codec {
+    table: BiMap<char, String>,
+    separator: String,
+    mode: Transform,
+}
+
+impl Codec {
+    // 从 CSV（列形如 `letter,code`）加载码表。
+    // 键或值出现重复时直接报错，以保证映射是真正一一对应、可逆的。
+    fn load(
+        path: &str,
+        separator: &str,
+        mode: Transform,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_path(path)?;
+
+        let mut table: BiMap<char, String> = BiMap::new();
+        for result in reader.records() {
+            let record = result?;
+            let letter_field = record
+                .get(0)
+                .ok_or("CSV 行缺少 letter 列")?
+                .to_string();
+            let code = record.get(1).ok_or("CSV 行缺少 code 列")?.to_string();
+
+            let mut chars = letter_field.chars();
+            let letter = chars
+                .next()
+                .ok_or_else(|| format!("letter 列为空：'{}'", letter_field))?;
+            if chars.next().is_some() {
+                return Err(format!("letter 列 '{}' 不是单个字符", letter_field).into());
+            }
+
+            table.insert_no_overwrite(letter, code).map_err(|(l, c)| {
+                format!("码表中存在重复的键或值：'{}' -> '{}'", l, c)
+            })?;
+        }
+
+        Ok(Codec {
+            table,
+            separator: separator.to_string(),
+            mode,
+        })
+    }
+
+    // 对一段文本执行转换。`--encode` 把每个字符映射成编码并用分隔符连接；
+    // `--decode` 把文本按分隔符切成 token 再逐个反查，遇到查不到的 token 会报错。
+    fn apply(&self, input: &str) -> Result<String, Box<dyn std::error::Error>> {
+        match self.mode {
+            Transform::Encode => {
+                // 和 Decode 对称：码表里没有的字符直接报错，而不是原样透传。
+                // 否则含有未映射字符的文本编码后将无法再被解码，违背可逆性。
+                let mut tokens = Vec::new();
+                let mut missing = Vec::new();
+                for ch in input.chars() {
+                    match self.table.get_by_left(&ch) {
+                        Some(code) => tokens.push(code.clone()),
+                        None => missing.push(ch),
+                    }
+                }
+                if !missing.is_empty() {
+                    let list: String = missing.iter().map(|c| format!("'{}'", c)).collect::<Vec<_>>().join(", ");
+                    return Err(format!("以下字符在码表中没有对应的编码：{}", list).into());
+                }
+                Ok(tokens.join(&self.separator))
+            }
+            Transform::Decode => {
+                let mut out = String::new();
+                let mut missing = Vec::new();
+                for token in input.split(&self.separator) {
+                    match self.table.get_by_right(token) {
+                        Some(ch) => out.push(*ch),
+                        None => missing.push(token.to_string()),
+                    }
+                }
+                if !missing.is_empty() {
+                    return Err(format!(
+                        "以下 token 在码表中没有反向映射：{}",
+                        missing.join(", ")
+                    )
+                    .into());
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+// 输出阶段可选的文本封装方式。
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum WrapFormat {
+    Base64,
+    Hex,
+}
+
+// 标准 base64 字母表。
+const BASE64_TABLE: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl WrapFormat {
+    // 把原始字节封装成文本。
+    fn wrap(self, data: &[u8]) -> String {
+        match self {
+            WrapFormat::Base64 => base64_encode(data),
+            WrapFormat::Hex => hex_encode(data),
+        }
+    }
+
+    // 把文本还原成原始字节。
+    fn unwrap(self, text: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self {
+            WrapFormat::Base64 => base64_decode(text),
+            WrapFormat::Hex => hex_decode(text),
+        }
+    }
+}
+
+// 按 3 字节一组、每组产出 4 个字符的方式做 base64 编码；
+// 末组剩 1 字节时补 2 个字符加 `==`，剩 2 字节时补 3 个字符加 `=`。
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b0 = chunk[0] as usize;
+        let b1 = *chunk.get(1).unwrap_or(&0) as usize;
+        let b2 = *chunk.get(2).unwrap_or(&0) as usize;
+
+        out.push(BASE64_TABLE[b0 >> 2] as char);
+        out.push(BASE64_TABLE[((b0 & 0b11) << 4) | (b1 >> 4)] as char);
+        match chunk.len() {
+            1 => out.push_str("=="),
+            2 => {
+                out.push(BASE64_TABLE[((b1 & 0b1111) << 2) | (b2 >> 6)] as char);
+                out.push('=');
+            }
+            _ => {
+                out.push(BASE64_TABLE[((b1 & 0b1111) << 2) | (b2 >> 6)] as char);
+                out.push(BASE64_TABLE[b2 & 0b111111] as char);
+            }
+        }
+    }
+    out
+}
+
+// base64 编码的逆运算。
+fn base64_decode(text: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    // 反查表：字符 -> 6 位数值。
+    let value = |c: u8| -> Result<u8, Box<dyn std::error::Error>> {
+        BASE64_TABLE
+            .iter()
+            .position(|&t| t == c)
+            .map(|p| p as u8)
+            .ok_or_else(|| format!("非法的 base64 字符: '{}'", c as char).into())
+    };
+
+    // 去掉换行等空白后按 4 字符一组处理。
+    let chars: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if chars.len() % 4 != 0 {
+        return Err("base64 输入长度不是 4 的倍数".into());
+    }
+
+    let mut out = Vec::with_capacity(chars.len() / 4 * 3);
+    for group in chars.chunks(4) {
+        let v0 = value(group[0])?;
+        let v1 = value(group[1])?;
+        out.push((v0 << 2) | (v1 >> 4));
+        if group[2] != b'=' {
+            let v2 = value(group[2])?;
+            out.push(((v1 & 0b1111) << 4) | (v2 >> 2));
+            if group[3] != b'=' {
+                let v3 = value(group[3])?;
+                out.push(((v2 & 0b11) << 6) | v3);
+            }
+        }
+    }
+    Ok(out)
+}
+
+// 每个字节输出两个小写十六进制字符。
+fn hex_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 2);
+    for byte in data {
+        out.push_str(&format!("{:02x}", byte));
+    }
+    out
+}
+
+// hex 编码的逆运算。
+fn hex_decode(text: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let chars: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if chars.len() % 2 != 0 {
+        return Err("hex 输入长度不是偶数".into());
+    }
+    let nibble = |c: u8| -> Result<u8, Box<dyn std::error::Error>> {
+        (c as char)
+            .to_digit(16)
+            .map(|d| d as u8)
+            .ok_or_else(|| format!("非法的 hex 字符: '{}'", c as char).into())
+    };
+    let mut out = Vec::with_capacity(chars.len() / 2);
+    for pair in chars.chunks(2) {
+        out.push((nibble(pair[0])? << 4) | nibble(pair[1])?);
+    }
+    Ok(out)
+}
+
 // `main` 函数是每个Rust可执行程序的入口点。
 fn main() {
     println!("启动文件编码转换器...");
 
-    let config_filename = "config.toml";
-    let config_content = match fs::read_to_string(config_filename) {
-        Ok(content) => content,
-        Err(e) => {
-            eprintln!("错误：无法读取配置文件 '{}': {}", config_filename, e);
+    // 第0步：解析命令行参数。
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Convert);
+    let jobs = cli.jobs;
+    let list_only = cli.list_only;
+    let backup_dest = cli.backup.clone();
+    let wrap = cli.wrap;
+    let unwrap = cli.unwrap;
+    let force = cli.force;
+
+    // 如果提供了码表，就按方向（`--encode`/`--decode`）把它加载成一个 `Codec`。
+    let codec = cli.table.as_ref().and_then(|table_path| {
+        let mode = if cli.decode {
+            Transform::Decode
+        } else if cli.encode {
+            Transform::Encode
+        } else {
+            eprintln!("错误：使用 --table 时必须指定 --encode 或 --decode。");
             exit(1);
+        };
+        match Codec::load(table_path, &cli.sep, mode) {
+            Ok(codec) => Some(codec),
+            Err(e) => {
+                eprintln!("错误：加载码表 '{}' 失败: {}", table_path, e);
+                exit(1);
+            }
         }
-    };
+    });
 
-    let config: Config = match toml::from_str(&config_content) {
-        Ok(parsed_config) => parsed_config,
-        Err(e) => {
-            eprintln!(
-                "错误：解析配置文件失败: 
+    // 如果提供了 `--match`，就把它编译成正则；非法表达式直接报错退出。
+    let match_re = cli.pattern.as_ref().map(|p| {
+        Regex::new(p).unwrap_or_else(|e| {
+            eprintln!("错误：无效的 --match 正则表达式 '{}': {}", p, e);
+            exit(1);
+        })
+    });
+
+    // 读取配置文件。注意：当命令行已经提供了全部必需字段时，
+    // 配置文件是可选的——读不到文件也不算错误。
+    let config: Config = match fs::read_to_string(&cli.config) {
+        Ok(content) => match toml::from_str(&content) {
+            Ok(parsed_config) => parsed_config,
+            Err(e) => {
+                eprintln!(
+                    "错误：解析配置文件失败:
 详情: {}",
-                e
+                    e
+                );
+                exit(1);
+            }
+        },
+        Err(_) => {
+            println!(
+                "提示：未找到或无法读取配置文件 '{}'，将完全依赖命令行参数。",
+                cli.config
             );
-            exit(1);
+            Config::default()
         }
     };
 
+    // 把命令行参数层叠在配置文件之上（命令行优先）。
+    let config = merge_config(cli, config);
+
     println!("配置加载成功: {:?}", config);
 
+    // `Detect` 子命令只检测编码、不改写文件。
+    let detect_only = command == Command::Detect;
+
     let output_encoding =
         Encoding::for_label(config.output_encoding.as_bytes()).unwrap_or_else(|| {
             eprintln!(
@@ -59,12 +547,36 @@ fn main() {
             UTF_8
         });
 
-    let path = PathBuf::from(config.path);
+    let path = PathBuf::from(&config.path);
     if !path.exists() {
-        eprintln!("错误：配置文件中指定的路径不存在: {}", path.display());
+        eprintln!("错误：指定的路径不存在: {}", path.display());
         exit(1);
     }
 
+    // `--list-only` 和 `Detect` 都是只读预览模式：它们承诺不改写磁盘，
+    // 因此既不建备份归档，也不写清单，让被扫描的目录保持原样。
+    let preview_only = list_only || detect_only;
+
+    // 如果指定了 `--backup`，就先建好归档；原始文件会在被覆盖前写进去。
+    // 预览模式下不创建归档，免得在只读运行里凭空产生文件。
+    let backup = backup_dest.as_ref().filter(|_| !preview_only).map(|dest| {
+        Backup::create(dest).unwrap_or_else(|e| {
+            eprintln!("错误：无法创建备份归档 '{}': {}", dest, e);
+            exit(1);
+        })
+    });
+
+    // 转换的根目录：目录模式下就是它本身，单文件模式下取其父目录。
+    // 备份归档里的相对路径和清单文件的位置都以它为基准。
+    let root = if path.is_dir() {
+        path.clone()
+    } else {
+        path.parent().unwrap_or_else(|| Path::new("")).to_path_buf()
+    };
+
+    // 加载（或在 `--force` 时忽略）清单，用于跳过未改动的文件。
+    let manifest = Manifest::load(&root, force);
+
     if path.is_dir() {
         println!("路径是一个目录。正在处理...");
         process_directory(
@@ -72,21 +584,92 @@ fn main() {
             &config.file_extension,
             output_encoding,
             &config.input_encodings,
+            detect_only,
+            jobs,
+            match_re.as_ref(),
+            list_only,
+            backup.as_ref(),
+            codec.as_ref(),
+            wrap,
+            unwrap,
+            &manifest,
         );
     } else if path.is_file() {
         println!("路径是一个文件。正在处理...");
-        match process_file(&path, output_encoding, &config.input_encodings) {
-            Ok(_) => println!("成功转换文件: {}", path.display()),
+        let mut log = Vec::new();
+        let result = process_file(
+            &path,
+            &root,
+            output_encoding,
+            &config.input_encodings,
+            detect_only,
+            match_re.as_ref(),
+            list_only,
+            backup.as_ref(),
+            codec.as_ref(),
+            wrap,
+            unwrap,
+            &manifest,
+            &mut log,
+        );
+        for line in &log {
+            println!("{}", line);
+        }
+        match result {
+            Ok(_) => println!("成功处理文件: {}", path.display()),
             Err(e) => {
-                eprintln!("转换文件 {} 时出错: {}", path.display(), e);
+                eprintln!("处理文件 {} 时出错: {}", path.display(), e);
                 exit(1);
             }
         }
     }
+
+    // 收尾：写出 tar 尾部并刷新 gzip 流。
+    if let Some(backup) = backup {
+        if let Err(e) = backup.finish() {
+            eprintln!("警告：写入备份归档时出错: {}", e);
+        } else if let Some(dest) = &backup_dest {
+            println!("备份已写入: {}", dest);
+        }
+    }
+
+    // 把本轮的哈希写回清单，供下次增量运行跳过未改动的文件。
+    // 预览模式（--list-only / Detect）不落盘清单，保持目录只读。
+    if !preview_only {
+        if let Err(e) = manifest.save(&root) {
+            eprintln!("警告：写入清单文件时出错: {}", e);
+        }
+    }
+
     println!("转换过程结束。");
     exit(0);
 }
 
+// 把命令行参数层叠在配置文件之上（命令行优先）。
+// 任何一个必需字段在两边都缺失时，都会直接报错退出。
+fn merge_config(cli: Cli, config: Config) -> ResolvedConfig {
+    // 一个小助手：优先用命令行的值，否则退回配置文件的值。
+    fn pick(cli_value: Option<String>, file_value: Option<String>, field: &str) -> String {
+        cli_value.or(file_value).unwrap_or_else(|| {
+            eprintln!(
+                "错误：缺少必需的配置项 '{}'（命令行和配置文件中都没有提供）。",
+                field
+            );
+            exit(1);
+        })
+    }
+
+    ResolvedConfig {
+        path: pick(cli.path, config.path, "path"),
+        file_extension: pick(cli.file_extension, config.file_extension, "file_extension"),
+        output_encoding: pick(cli.output_encoding, config.output_encoding, "output_encoding"),
+        input_encodings: cli.input_encodings.or(config.input_encodings).unwrap_or_else(|| {
+            eprintln!("错误：缺少必需的配置项 'input_encodings'（命令行和配置文件中都没有提供）。");
+            exit(1);
+        }),
+    }
+}
+
 fn exit(code: i32) -> ! {
     println!("按任意键退出...");
     terminal::enable_raw_mode().unwrap();
@@ -100,20 +683,114 @@ fn exit(code: i32) -> ! {
 }
 
 // 处理目录
+#[allow(clippy::too_many_arguments)]
 fn process_directory(
     path: &Path,
     ext: &str,
     output_encoding: &'static Encoding,
     input_encodings: &[String],
+    detect_only: bool,
+    jobs: Option<usize>,
+    match_re: Option<&Regex>,
+    list_only: bool,
+    backup: Option<&Backup>,
+    codec: Option<&Codec>,
+    wrap: Option<WrapFormat>,
+    unwrap: bool,
+    manifest: &Manifest,
 ) {
-    for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
-        if entry.path().is_file() && entry.path().extension().map_or(false, |e| e == ext) {
-            match process_file(entry.path(), output_encoding, input_encodings) {
-                Ok(_) => println!("  -> 成功转换: {}", entry.path().display()),
-                Err(e) => eprintln!("  -> 转换 {} 时出错: {}", entry.path().display(), e),
+    // 先把所有匹配扩展名的文件收集起来，再并行处理。
+    let files: Vec<PathBuf> = WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| {
+            entry.path().is_file() && entry.path().extension().map_or(false, |e| e == ext)
+        })
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+
+    if files.is_empty() {
+        println!("  -> 没有找到扩展名为 '{}' 的文件。", ext);
+        return;
+    }
+
+    // 每个文件产出一份 `FileReport`。`par_iter` 会用 rayon 的线程池并发处理，
+    // `--jobs` 则用来限制线程数；不指定时交给 rayon 按 CPU 核心数决定。
+    let run = || -> Vec<FileReport> {
+        files
+            .par_iter()
+            .map(|file_path| {
+                let mut log = Vec::new();
+                let result = process_file(
+                    file_path,
+                    path,
+                    output_encoding,
+                    input_encodings,
+                    detect_only,
+                    match_re,
+                    list_only,
+                    backup,
+                    codec,
+                    wrap,
+                    unwrap,
+                    manifest,
+                    &mut log,
+                )
+                .map_err(|e| e.to_string());
+                FileReport {
+                    path: file_path.clone(),
+                    result,
+                    log,
+                }
+            })
+            .collect()
+    };
+
+    let mut reports = match jobs {
+        Some(n) => rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build()
+            .map(|pool| pool.install(run))
+            .unwrap_or_else(|e| {
+                eprintln!("警告：无法创建大小为 {} 的线程池（{}），退回默认并行度。", n, e);
+                run()
+            }),
+        None => run(),
+    };
+
+    // 按路径排序后再打印，得到稳定、可读的输出，而不是多个线程交错的结果。
+    reports.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut converted = 0usize;
+    let mut skipped = 0usize;
+    let mut failed = 0usize;
+    for report in &reports {
+        for line in &report.log {
+            println!("{}", line);
+        }
+        match &report.result {
+            Ok(FileOutcome::Converted) => {
+                converted += 1;
+                println!("  -> 成功处理: {}", report.path.display());
+            }
+            Ok(FileOutcome::Skipped) => {
+                skipped += 1;
+                println!("  -> 已跳过: {}", report.path.display());
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("  -> 处理 {} 时出错: {}", report.path.display(), e);
             }
         }
     }
+
+    println!(
+        "汇总：成功 {} 个，跳过 {} 个，失败 {} 个（共 {} 个文件）。",
+        converted,
+        skipped,
+        failed,
+        reports.len()
+    );
 }
 
 // 尝试自动检测编码
@@ -128,26 +805,64 @@ fn detect_encoding(buffer: &[u8]) -> Option<(&'static Encoding, usize)> {
 }
 
 // 处理单个文件
+#[allow(clippy::too_many_arguments)]
 fn process_file(
     file_path: &Path,
+    root: &Path,
     output_encoding: &'static Encoding,
     input_encodings: &[String],
-) -> Result<(), Box<dyn std::error::Error>> {
+    detect_only: bool,
+    match_re: Option<&Regex>,
+    list_only: bool,
+    backup: Option<&Backup>,
+    codec: Option<&Codec>,
+    wrap: Option<WrapFormat>,
+    unwrap: bool,
+    manifest: &Manifest,
+    log: &mut Vec<String>,
+) -> Result<FileOutcome, Box<dyn std::error::Error>> {
     let mut buffer = Vec::new();
     fs::File::open(file_path)?.read_to_end(&mut buffer)?;
 
+    // 相对根目录的路径，既用于备份归档，也作为清单里的键。
+    let rel_path = file_path.strip_prefix(root).unwrap_or(file_path);
+    let rel_key = rel_path.to_string_lossy().into_owned();
+
+    // --- 第0步: 先对当前磁盘内容求哈希。哈希命中清单说明上一轮已转换且未改动，
+    // 直接跳过，不再检测和改写。`--list-only` 预览和 `detect` 子命令不参考清单，
+    // 这样它们仍能覆盖所有文件。
+    let current_hash = content_hash(&buffer);
+    if !detect_only && !list_only && manifest.is_unchanged(&rel_key, &current_hash) {
+        log.push(format!("    - 哈希未变，跳过（清单命中）: {}", rel_path.display()));
+        return Ok(FileOutcome::Skipped);
+    }
+
+    // 保留一份磁盘上的原始字节：`--unwrap` 会改写 `buffer`，而“内容未变”的判断
+    // 和备份归档都必须针对真正写在磁盘上的内容，而不是还原后的中间结果。
+    let original_bytes = buffer.clone();
+
+    // 如果指定了 `--unwrap`，先把文件内容从 base64/hex 文本还原成原始字节，
+    // 之后的编码检测与转换都作用在还原后的字节上。
+    if unwrap {
+        if let Some(format) = wrap {
+            let text = String::from_utf8_lossy(&buffer);
+            buffer = format.unwrap(&text)?;
+            log.push(format!("    - 已从 {:?} 文本还原出原始字节。", format));
+        }
+    }
+
     let mut source_encoding_opt: Option<(&'static Encoding, usize)> = None;
 
     // --- 第1步: 尝试自动检测编码
     if let Some((encoding, bom_len)) = detect_encoding(&buffer) {
-        println!(
+        log.push(format!(
             "    - 自动检测到编码: {} 位于 {}",
             encoding.name(),
             file_path.display()
-        );
+        ));
         source_encoding_opt = Some((encoding, bom_len));
     } else {
-        println!("    - 自动检测失败。尝试配置文件中的备选编码...");
+        log.push("    - 自动检测失败。尝试配置文件中的备选编码...".to_string());
     }
 
     // --- 第2步: 如果自动检测失败，则尝试配置文件中的编码列表
@@ -157,11 +872,11 @@ fn process_file(
     } else {
         let mut decoded_result: Option<(String, String)> = None;
         for encoding_name in input_encodings {
-            println!("    - 尝试使用备选编码 '{}' 解码……", encoding_name);
+            log.push(format!("    - 尝试使用备选编码 '{}' 解码……", encoding_name));
             if let Some(encoding) = Encoding::for_label(encoding_name.as_bytes()) {
                 let (decoded, _, had_errors) = encoding.decode(&buffer);
                 if !had_errors {
-                    println!("    - 成功使用备选编码 '{}' 解码", encoding_name);
+                    log.push(format!("    - 成功使用备选编码 '{}' 解码", encoding_name));
                     decoded_result = Some((decoded.to_string(), encoding_name.clone()));
                     break;
                 }
@@ -177,17 +892,151 @@ fn process_file(
         }
     };
 
-    println!("    - 使用编码 '{}' 进行转换。", used_encoding_name);
+    log.push(format!("    - 使用编码 '{}' 进行转换。", used_encoding_name));
+
+    // --- 内容过滤：只有解码后的内容匹配正则的文件才继续转换。
+    // 匹配发生在解码成 `String` 之后，因此与源编码无关。
+    if let Some(re) = match_re {
+        let matches: Vec<(usize, &str)> = decoded_str
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| re.is_match(line))
+            .map(|(i, line)| (i + 1, line))
+            .collect();
+
+        if matches.is_empty() {
+            log.push("    - 内容不匹配 --match 模式，已跳过。".to_string());
+            return Ok(FileOutcome::Skipped);
+        }
+
+        // `--list-only` 像 grep 一样打印路径和行号，不改写任何文件。
+        if list_only {
+            for (lineno, line) in &matches {
+                log.push(format!("{}:{}:{}", file_path.display(), lineno, line));
+            }
+            return Ok(FileOutcome::Skipped);
+        }
+    }
+
+    // 在仅检测模式下，到此为止：只报告检测结果，不改写文件。
+    if detect_only {
+        log.push("    - （仅检测模式）未写入任何改动。".to_string());
+        return Ok(FileOutcome::Skipped);
+    }
+
+    // 如果启用了码表转换，就在重新编码之前对解码后的文本做一次可逆变换。
+    let content = match codec {
+        Some(codec) => {
+            let transformed = codec.apply(&decoded_str)?;
+            log.push("    - 已应用码表转换。".to_string());
+            transformed
+        }
+        None => decoded_str.into_owned(),
+    };
 
-    // --- 第3步: 将解码后的字符串用目标编码重新编码
-    let (encoded_bytes, _, had_errors) = output_encoding.encode(&decoded_str);
+    // --- 第3步: 将转换后的字符串用目标编码重新编码
+    let (encoded_bytes, _, had_errors) = output_encoding.encode(&content);
     if had_errors {
         return Err(format!("无法将内容编码到 '{}'", output_encoding.name()).into());
     }
 
-    // --- 第4步: 将新编码的字节写回文件
-    fs::File::create(file_path)?.write_all(&encoded_bytes)?;
-    println!("    - 文件已成功用 {} 编码覆盖。", output_encoding.name());
+    // 如果启用了 `--wrap`（且不是 `--unwrap`），在写回前把字节再做一层文本编码。
+    let output_bytes: Vec<u8> = match wrap {
+        Some(format) if !unwrap => {
+            let wrapped = format.wrap(&encoded_bytes);
+            log.push(format!("    - 已用 {:?} 对输出进行封装。", format));
+            wrapped.into_bytes()
+        }
+        _ => encoded_bytes.into_owned(),
+    };
+
+    // 如果最终要写回的字节和磁盘上的原始内容完全一致，说明这个文件无需改动：
+    // 既不写回，也不放进备份归档，这样备份里只包含真正被修改的文件。
+    // 注意这里比较的是原始字节而非 `--unwrap` 还原后的中间结果。
+    if output_bytes == original_bytes {
+        log.push("    - 内容未发生变化，已跳过（未写入、未备份）。".to_string());
+        return Ok(FileOutcome::Skipped);
+    }
+
+    // --- 第4步: 在覆盖前，把磁盘上的原始字节写入备份归档（如果启用了 --backup）。
+    if let Some(backup) = backup {
+        backup.add(rel_path, &original_bytes)?;
+        log.push(format!("    - 已备份原始文件: {}", rel_path.display()));
+    }
+
+    // --- 第5步: 将新编码的字节写回文件
+    fs::File::create(file_path)?.write_all(&output_bytes)?;
+    log.push(format!("    - 文件已成功用 {} 编码覆盖。", output_encoding.name()));
+
+    // 记录写回内容的哈希，下一轮运行据此判断文件是否改动过。
+    manifest.record(rel_key, content_hash(&output_bytes));
+
+    Ok(FileOutcome::Converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 用一张小码表直接构造一个 Codec，省去读取 CSV 文件。
+    fn sample_codec(mode: Transform) -> Codec {
+        let mut table: BiMap<char, String> = BiMap::new();
+        table.insert_no_overwrite('a', "1".to_string()).unwrap();
+        table.insert_no_overwrite('b', "2".to_string()).unwrap();
+        table.insert_no_overwrite('c', "3".to_string()).unwrap();
+        Codec {
+            table,
+            separator: " ".to_string(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn base64_known_vectors() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+        assert_eq!(base64_encode(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
 
-    Ok(())
+    #[test]
+    fn base64_round_trips_all_padding() {
+        for input in [&b""[..], b"f", b"fo", b"foo", b"any carnal pleasure."] {
+            let encoded = base64_encode(input);
+            assert_eq!(base64_decode(&encoded).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn base64_decode_rejects_bad_input() {
+        assert!(base64_decode("Zg=").is_err()); // 长度不是 4 的倍数
+        assert!(base64_decode("****").is_err()); // 非法字符
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(hex_encode(b"\x00\x0f\xff"), "000fff");
+        assert_eq!(hex_decode("000fff").unwrap(), b"\x00\x0f\xff");
+        assert!(hex_decode("abc").is_err()); // 长度为奇数
+        assert!(hex_decode("zz").is_err()); // 非法字符
+    }
+
+    #[test]
+    fn codec_encodes_and_decodes() {
+        let encoded = sample_codec(Transform::Encode).apply("abc").unwrap();
+        assert_eq!(encoded, "1 2 3");
+        let decoded = sample_codec(Transform::Decode).apply("1 2 3").unwrap();
+        assert_eq!(decoded, "abc");
+    }
+
+    #[test]
+    fn codec_errors_on_unmapped() {
+        // 源字符不在码表里 -> 编码报错（保证可逆）。
+        assert!(sample_codec(Transform::Encode).apply("axc").is_err());
+        // token 没有反向映射 -> 解码报错。
+        assert!(sample_codec(Transform::Decode).apply("1 9 3").is_err());
+    }
 }